@@ -0,0 +1,98 @@
+mod render;
+mod vm;
+
+pub use render::Renderer;
+pub use vm::{Direction, Halt, TraceEvent, VM};
+
+use crate::vm::Direction as Dir;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MatrixPoint(pub usize, pub usize);
+
+#[derive(Clone, Debug)]
+pub struct Matrix<T> {
+    pub matrix: Vec<Vec<T>>,
+}
+
+impl<T: Copy> Matrix<T> {
+    pub fn new(matrix: Vec<Vec<T>>) -> Self {
+        Matrix { matrix }
+    }
+
+    // the pixel one step away from `point` in `dir`, or `None` at the edge of the matrix
+    pub fn go(&self, point: MatrixPoint, dir: Dir) -> Option<T> {
+        let MatrixPoint(x, y) = point;
+        let (dx, dy) = dir.offset();
+        let x = x.checked_add_signed(dx)?;
+        let y = y.checked_add_signed(dy)?;
+        self.matrix.get(y)?.get(x).copied()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Instruction {
+    Start,
+    Road,
+    Halt,
+    Push,
+    Pop,
+    Add,
+    Sub,
+    Load,
+    Store,
+    TapeLeft,
+    TapeRight,
+    RotateClockwise,
+    RotateCounterClockwise,
+    MirrorSlash,
+    MirrorBackslash,
+    SplitterHorizontal,
+    SplitterVertical,
+}
+
+// a pixel's color encodes its instruction as a hue; `new` takes that raw hue (0-359)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Pixel(u16);
+
+impl Pixel {
+    pub fn new(hue: u16) -> Pixel {
+        Pixel(hue % 360)
+    }
+
+    pub fn as_instruction(&self) -> Instruction {
+        match self.0 {
+            300 => Instruction::Start,
+            324 => Instruction::Halt,
+            36 => Instruction::Push,
+            48 => Instruction::Pop,
+            72 => Instruction::Add,
+            96 => Instruction::Sub,
+            108 => Instruction::Load,
+            120 => Instruction::Store,
+            144 => Instruction::TapeLeft,
+            156 => Instruction::TapeRight,
+            180 => Instruction::RotateClockwise,
+            192 => Instruction::RotateCounterClockwise,
+            204 => Instruction::MirrorSlash,
+            216 => Instruction::MirrorBackslash,
+            228 => Instruction::SplitterHorizontal,
+            240 => Instruction::SplitterVertical,
+            _ => Instruction::Road,
+        }
+    }
+
+    // full-saturation, full-value HSV -> RGB, reused by the trace renderer
+    pub fn color(&self) -> image::Rgb<u8> {
+        let h = self.0 as f32 / 60.0;
+        let x = 1.0 - (h % 2.0 - 1.0).abs();
+        let (r, g, b) = match h as u16 {
+            0 => (1.0, x, 0.0),
+            1 => (x, 1.0, 0.0),
+            2 => (0.0, 1.0, x),
+            3 => (0.0, x, 1.0),
+            4 => (x, 0.0, 1.0),
+            _ => (1.0, 0.0, x),
+        };
+        image::Rgb([(r * 255.0) as u8, (g * 255.0) as u8, (b * 255.0) as u8])
+    }
+}
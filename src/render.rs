@@ -0,0 +1,131 @@
+//! Turns a recorded `VM` trace back into images.
+//!
+//! quilt programs are literally grids of colored pixels, so visualizing a run is
+//! mostly bookkeeping: redraw the instruction grid once per step, then overlay a
+//! marker on every walker's pixel for that step (reusing `Pixel`'s existing
+//! pixel-to-color mapping for the grid itself).
+
+use image::buffer::ConvertBuffer;
+use image::{Delay, Frame, ImageBuffer, ImageResult, Rgb, RgbImage};
+
+use crate::vm::TraceEvent;
+use crate::{Matrix, MatrixPoint, Pixel};
+
+/// Size, in output pixels, of one quilt pixel in a rendered frame.
+const CELL_SIZE: u32 = 16;
+
+/// Color used to highlight a walker's position on top of the instruction grid.
+const MARKER_COLOR: Rgb<u8> = Rgb([255, 255, 255]);
+
+pub struct Renderer<'a> {
+    instructions: &'a Matrix<Pixel>,
+}
+
+impl<'a> Renderer<'a> {
+    pub fn new(instructions: &'a Matrix<Pixel>) -> Self {
+        Renderer { instructions }
+    }
+
+    /// Render a single frame: the instruction grid with every walker alive at
+    /// `event` highlighted on top of its pixel.
+    pub fn render_frame(&self, event: &TraceEvent) -> RgbImage {
+        let height = self.instructions.matrix.len() as u32;
+        let width = self
+            .instructions
+            .matrix
+            .first()
+            .map_or(0, |row| row.len()) as u32;
+        let mut frame = ImageBuffer::new(width * CELL_SIZE, height * CELL_SIZE);
+
+        for (y, row) in self.instructions.matrix.iter().enumerate() {
+            for (x, pixel) in row.iter().enumerate() {
+                paint_cell(&mut frame, x as u32, y as u32, pixel.color());
+            }
+        }
+
+        for (point, _direction) in &event.walkers {
+            let MatrixPoint(x, y) = *point;
+            paint_marker(&mut frame, x as u32, y as u32);
+        }
+
+        frame
+    }
+
+    /// Render a whole trace as an animated GIF, one frame per recorded step.
+    pub fn render_gif<W: std::io::Write>(
+        &self,
+        trace: &[TraceEvent],
+        frame_delay_ms: u32,
+        out: W,
+    ) -> ImageResult<()> {
+        use image::codecs::gif::{GifEncoder, Repeat};
+
+        let mut encoder = GifEncoder::new(out);
+        encoder.set_repeat(Repeat::Infinite)?;
+
+        let delay = Delay::from_saturating_duration(std::time::Duration::from_millis(
+            frame_delay_ms as u64,
+        ));
+        for event in trace {
+            let frame: image::RgbaImage = self.render_frame(event).convert();
+            encoder.encode_frame(Frame::from_parts(frame, 0, 0, delay))?;
+        }
+
+        Ok(())
+    }
+}
+
+fn paint_cell(frame: &mut RgbImage, x: u32, y: u32, color: Rgb<u8>) {
+    for dy in 0..CELL_SIZE {
+        for dx in 0..CELL_SIZE {
+            frame.put_pixel(x * CELL_SIZE + dx, y * CELL_SIZE + dy, color);
+        }
+    }
+}
+
+fn paint_marker(frame: &mut RgbImage, x: u32, y: u32) {
+    let margin = CELL_SIZE / 4;
+    for dy in margin..(CELL_SIZE - margin) {
+        for dx in margin..(CELL_SIZE - margin) {
+            frame.put_pixel(x * CELL_SIZE + dx, y * CELL_SIZE + dy, MARKER_COLOR);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::vm::{Direction, TraceEvent};
+
+    #[test]
+    fn test_render_frame_scales_the_grid_by_cell_size() {
+        let instructions = Matrix::new(vec![
+            vec![Pixel::new(0), Pixel::new(0)],
+            vec![Pixel::new(0), Pixel::new(0)],
+            vec![Pixel::new(0), Pixel::new(0)],
+        ]);
+        let renderer = Renderer::new(&instructions);
+
+        let frame = renderer.render_frame(&TraceEvent {
+            step: 0,
+            walkers: vec![],
+        });
+
+        assert_eq!(frame.width(), 2 * CELL_SIZE);
+        assert_eq!(frame.height(), 3 * CELL_SIZE);
+    }
+
+    #[test]
+    fn test_render_frame_paints_a_marker_at_each_walker() {
+        let instructions = Matrix::new(vec![vec![Pixel::new(0), Pixel::new(0)]]);
+        let renderer = Renderer::new(&instructions);
+
+        let frame = renderer.render_frame(&TraceEvent {
+            step: 0,
+            walkers: vec![(MatrixPoint(1, 0), Direction::East)],
+        });
+
+        let center = frame.get_pixel(CELL_SIZE + CELL_SIZE / 2, CELL_SIZE / 2);
+        assert_eq!(*center, MARKER_COLOR);
+    }
+}
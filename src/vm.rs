@@ -1,7 +1,62 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
 use crate::{Instruction, Pixel};
 use crate::{Matrix, MatrixPoint};
 
+/// A single beam traveling through the matrix: its position and the direction it's
+/// heading. Splitters fork a walker into two.
+pub(crate) type Walker = (MatrixPoint, Direction);
+
+/// Every walker alive at the start of a given step, recorded when tracing is enabled.
+/// `crate::render` turns a sequence of these into frames.
+#[derive(Clone, Debug)]
+pub struct TraceEvent {
+    pub step: usize,
+    pub walkers: Vec<Walker>,
+}
+
+/// A full snapshot of everything that determines the machine's future behavior.
+/// Because `quilt` programs are deterministic, seeing the same `State` twice means
+/// the run is in a cycle and would repeat that cycle forever.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct State {
+    pc: MatrixPoint,
+    direction: Direction,
+    register_a: u16,
+    stack: Vec<u16>,
+    tape: [u16; TAPE_SIZE],
+}
+
+/// What a single walker's lineage has personally lived through, keyed per-walker
+/// rather than globally so that one walker's path never falsely implicates another.
+/// Cloned into both children when a splitter forks a walker.
+#[derive(Clone, Default)]
+struct History {
+    /// `(position, direction)` pairs this lineage has already stepped through.
+    visited: HashSet<Walker>,
+    /// Full machine-state snapshots this lineage has already passed through, and the
+    /// step at which each was first seen.
+    seen: HashMap<State, usize>,
+}
+
 const TAPE_SIZE: usize = 360;
+const DEFAULT_MAX_STEPS: usize = 1_000_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Halt {
+    /// A `Halt` instruction was reached, or every walker ended without a full-state
+    /// cycle: it had nowhere to go, or it revisited one of its own
+    /// `(position, direction)` pairs.
+    Normal,
+    /// The step budget (`execute_with_budget`'s `max_steps`, or `execute`'s default)
+    /// was exhausted before the program halted.
+    StepBudgetExceeded,
+    /// No walker ever reached `Halt`, and at least one walker re-entered, `period` steps
+    /// later, the full machine state it was first in at step `start_step`, proving it
+    /// would otherwise loop forever. If any *other* walker did reach `Halt`, the run is
+    /// `Halt::Normal` instead — a dead-looping sibling doesn't change that outcome.
+    Cycle { start_step: usize, period: usize },
+}
 
 pub struct VM {
     stack: Vec<u16>,
@@ -10,9 +65,10 @@ pub struct VM {
     direction: Direction,
     instructions: Matrix<Pixel>,
     pc: MatrixPoint,
+    trace: Option<Vec<TraceEvent>>,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Direction {
     North,
     East,
@@ -38,6 +94,42 @@ impl Direction {
             Direction::East => Direction::North,
         }
     }
+
+    pub fn clockwise(&self) -> Direction {
+        match self {
+            Direction::North => Direction::East,
+            Direction::East => Direction::South,
+            Direction::South => Direction::West,
+            Direction::West => Direction::North,
+        }
+    }
+
+    pub fn turn_left(&self) -> Direction {
+        self.counter_clockwise()
+    }
+
+    pub fn turn_right(&self) -> Direction {
+        self.clockwise()
+    }
+
+    /// The unit step `(dx, dy)` taken by moving one pixel in this direction.
+    pub fn offset(&self) -> (isize, isize) {
+        match self {
+            Direction::North => (0, -1),
+            Direction::South => (0, 1),
+            Direction::East => (1, 0),
+            Direction::West => (-1, 0),
+        }
+    }
+
+    pub fn all() -> [Direction; 4] {
+        [
+            Direction::North,
+            Direction::East,
+            Direction::South,
+            Direction::West,
+        ]
+    }
 }
 
 impl Default for VM {
@@ -49,6 +141,7 @@ impl Default for VM {
             direction: Direction::East,
             instructions: Matrix::new(vec![]),
             pc: MatrixPoint(0, 0),
+            trace: None,
         }
     }
 }
@@ -58,62 +151,298 @@ impl VM {
         VM::default()
     }
 
-    pub fn execute(&mut self, instructions: Matrix<Pixel>) {
+    pub fn execute(&mut self, instructions: Matrix<Pixel>) -> Halt {
+        self.execute_with_budget(instructions, DEFAULT_MAX_STEPS)
+    }
+
+    /// Start recording a `TraceEvent` per step, retrievable afterwards via `trace()`.
+    /// Tracing is off by default since most callers don't want to pay for it.
+    pub fn enable_trace(&mut self) {
+        self.trace = Some(Vec::new());
+    }
+
+    /// The trace recorded by the most recent `execute`/`execute_with_budget` call, if
+    /// `enable_trace` was called beforehand.
+    pub fn trace(&self) -> Option<&[TraceEvent]> {
+        self.trace.as_deref()
+    }
+
+    /// Like `execute`, but stops with `Halt::StepBudgetExceeded` after `max_steps` steps
+    /// if the program hasn't halted by then. Use this for programs that aren't trusted
+    /// to terminate on their own.
+    ///
+    /// Splitters can fork the PC into several concurrently-advancing walkers. `stack`,
+    /// `register_a` and `tape` are *shared* across all of them rather than copied on
+    /// fork, so forked beams can hand data to each other through the stack/tape; only a
+    /// walker's position and direction are its own.
+    ///
+    /// Each walker carries its own [`History`]: the `(position, direction)` pairs and
+    /// full machine-state snapshots it has personally passed through. `History` is
+    /// cloned into both children when a splitter forks a walker, so siblings track
+    /// their own history from the fork point on rather than polluting each other's —
+    /// two walkers that happen to cross the same cell are not the same walker.
+    ///
+    /// Before a walker is stepped, a snapshot of the full machine state (its `pc`,
+    /// `direction`, `register_a`, `stack` and `tape`) is checked against every snapshot
+    /// *that walker's lineage* has seen so far. A repeat proves that lineage would loop
+    /// forever from here on, so it's dropped just like a walker that revisits one of its
+    /// own `(position, direction)` pairs — on its own, a lineage looping forever doesn't
+    /// tell us anything about whether any *other* walker is stuck, and a sibling may
+    /// still be on its way to a legitimate `Halt`.
+    ///
+    /// `Halt::Cycle` is therefore only ever the *final* answer: it's reported once the
+    /// walker queue has completely drained and at least one lineage proved itself
+    /// non-terminating this way, with no walker ever having reached a `Halt` instruction
+    /// to redeem the run. If any walker does reach `Halt`, the run is `Halt::Normal`
+    /// regardless of how many siblings were dropped for looping — their fate no longer
+    /// changes the outcome.
+    pub fn execute_with_budget(&mut self, instructions: Matrix<Pixel>, max_steps: usize) -> Halt {
         self.instructions = instructions;
         self.pc = self.find_start();
 
-        //loop { // TODO change condition
-        //}
+        if let Some(trace) = self.trace.as_mut() {
+            trace.clear();
+        }
+
+        let mut walkers = VecDeque::from([((self.pc, self.direction), History::default())]);
+        let mut reached_halt = false;
+        let mut cycle: Option<Halt> = None;
+
+        for step in 0..max_steps {
+            if let Some(trace) = self.trace.as_mut() {
+                trace.push(TraceEvent {
+                    step,
+                    walkers: walkers.iter().map(|(walker, _)| *walker).collect(),
+                });
+            }
+
+            let Some((walker @ (point, dir), mut history)) = walkers.pop_front() else {
+                // every beam has halted, bounced into a dead end, or been dropped for
+                // looping; a cycle only counts if nothing else ever legitimately halted
+                return if reached_halt {
+                    Halt::Normal
+                } else {
+                    cycle.unwrap_or(Halt::Normal)
+                };
+            };
+
+            self.pc = point;
+            self.direction = dir;
+
+            let state = self.fingerprint();
+            if let Some(&start_step) = history.seen.get(&state) {
+                // this lineage would loop forever on its own; drop just it and remember
+                // the cycle in case no other walker ever reaches Halt
+                cycle.get_or_insert(Halt::Cycle {
+                    start_step,
+                    period: step - start_step,
+                });
+                continue;
+            }
+            history.seen.insert(state, step);
+
+            if !history.visited.insert(walker) {
+                // this walker has already been here heading this way; drop just it
+                continue;
+            }
+
+            if let Instruction::Halt = self.pixel_at(point).as_instruction() {
+                reached_halt = true;
+                continue;
+            }
+
+            self.apply(self.pixel_at(point).as_instruction());
+            walkers.extend(
+                self.branch()
+                    .into_iter()
+                    .map(|child| (child, history.clone())),
+            );
+        }
+
+        // the last walker may have finished on the final budgeted step
+        if walkers.is_empty() {
+            return if reached_halt {
+                Halt::Normal
+            } else {
+                cycle.unwrap_or(Halt::Normal)
+            };
+        }
+
+        Halt::StepBudgetExceeded
+    }
+
+    fn fingerprint(&self) -> State {
+        State {
+            pc: self.pc,
+            direction: self.direction,
+            register_a: self.register_a,
+            stack: self.stack.clone(),
+            tape: self.tape,
+        }
+    }
+
+    // compute where a walker currently sitting at (self.pc, self.direction) goes next.
+    // Ordinarily that's a single successor (road/bounce routing, with reflectors
+    // deflecting as they're entered), but a splitter entered perpendicular to its axis
+    // forks into two walkers heading in opposite directions.
+    fn branch(&mut self) -> Vec<Walker> {
+        let split = match (self.pixel_at(self.pc).as_instruction(), self.direction) {
+            (Instruction::SplitterHorizontal, Direction::North | Direction::South) => {
+                Some((Direction::East, Direction::West))
+            }
+            (Instruction::SplitterVertical, Direction::East | Direction::West) => {
+                Some((Direction::North, Direction::South))
+            }
+            _ => None,
+        };
+
+        if let Some((a, b)) = split {
+            return [a, b]
+                .into_iter()
+                .filter_map(|dir| self.step(dir).map(|(point, _)| (point, dir)))
+                .collect();
+        }
+
+        match self.get_next_step() {
+            Some((dir, point)) => {
+                self.direction = dir;
+                self.pc = point;
+                self.reflect();
+                vec![(self.pc, self.direction)]
+            }
+            // nowhere to go from here; this walker is done
+            None => vec![],
+        }
+    }
+
+    // a mirror deflects the PC based purely on the direction it was entered with, so
+    // apply that deflection as soon as we land on one, before the next step is computed
+    fn reflect(&mut self) {
+        self.direction = match self.pixel_at(self.pc).as_instruction() {
+            Instruction::MirrorSlash => match self.direction {
+                Direction::East => Direction::North,
+                Direction::North => Direction::East,
+                Direction::West => Direction::South,
+                Direction::South => Direction::West,
+            },
+            Instruction::MirrorBackslash => match self.direction {
+                Direction::East => Direction::South,
+                Direction::South => Direction::East,
+                Direction::West => Direction::North,
+                Direction::North => Direction::West,
+            },
+            _ => self.direction,
+        };
+    }
+
+    fn apply(&mut self, instruction: Instruction) {
+        match instruction {
+            Instruction::Push => self.stack.push(self.register_a),
+            Instruction::Pop => self.register_a = self.stack.pop().unwrap_or(0),
+            Instruction::Add => self.binary_op(u16::wrapping_add),
+            Instruction::Sub => self.binary_op(u16::wrapping_sub),
+            Instruction::Load => self.stack.push(self.tape[self.tape_index()]),
+            Instruction::Store => {
+                let value = self.stack.pop().unwrap_or(0);
+                let index = self.tape_index();
+                self.tape[index] = value;
+            }
+            Instruction::TapeLeft => self.register_a = self.register_a.wrapping_sub(1),
+            Instruction::TapeRight => self.register_a = self.register_a.wrapping_add(1),
+            Instruction::RotateClockwise => self.direction = self.direction.clockwise(),
+            Instruction::RotateCounterClockwise => {
+                self.direction = self.direction.counter_clockwise();
+            }
+            _ => {}
+        }
+    }
+
+    fn binary_op(&mut self, op: fn(u16, u16) -> u16) {
+        let b = self.stack.pop().unwrap_or(0);
+        let a = self.stack.pop().unwrap_or(0);
+        self.stack.push(op(a, b));
     }
 
-    // prioritize roads over all other instructions besides the one in front of us
-    pub fn get_next_instruction(&self) -> Pixel {
+    fn tape_index(&self) -> usize {
+        self.register_a as usize % TAPE_SIZE
+    }
+
+    fn pixel_at(&self, point: MatrixPoint) -> Pixel {
+        let MatrixPoint(x, y) = point;
+        self.instructions.matrix[y][x]
+    }
+
+    // prioritize roads, reflectors and splitters over all other instructions besides
+    // the one in front of us: reflectors route the PC just like roads do, differing
+    // only in that they also deflect `self.direction` once the PC lands on them, and
+    // splitters route (or fork, via `branch`) rather than ever being bounce targets.
+    // Returns the direction and position the PC should move to next, or `None` if
+    // there's nowhere to go (the current pixel has no reachable neighbors at all).
+    pub fn get_next_step(&self) -> Option<(Direction, MatrixPoint)> {
         let next_pixels = self.get_next_pixels();
-        let (first_dir, first_pixel) = next_pixels[0];
-        let first_road = next_pixels
-            .iter()
-            .filter(|(_dir, pixel)| matches!(pixel.as_instruction(), Instruction::Road))
-            .next();
-        if let Some((dir, road)) = first_road {
-            if *dir != self.direction.opposite() {
-                return *road;
+        let (first_dir, first_point, first_pixel) = *next_pixels.first()?;
+        // a Halt directly ahead must be reached so the walker can stop there; it must
+        // never be routed around in favor of a road/mirror/splitter side-branch
+        if let Instruction::Halt = first_pixel.as_instruction() {
+            return Some((first_dir, first_point));
+        }
+        let first_road = next_pixels.iter().find(|(_dir, _point, pixel)| {
+            matches!(
+                pixel.as_instruction(),
+                Instruction::Road
+                    | Instruction::MirrorSlash
+                    | Instruction::MirrorBackslash
+                    | Instruction::SplitterHorizontal
+                    | Instruction::SplitterVertical
+            )
+        });
+        if let Some(&(dir, point, _road)) = first_road {
+            if dir != self.direction.opposite() {
+                return Some((dir, point));
             } else if first_dir == self.direction {
-                return first_pixel;
+                return Some((first_dir, first_point));
             }
         }
         // We bounce
-        next_pixels.last().unwrap().1
+        let (dir, point, _) = *next_pixels.last()?;
+        Some((dir, point))
     }
 
     // try the pixel ahead of us. If that doesn't exist,
-    // try the pixel to the 'right' (counter-clockwise & opposite). If that doesn't exist,
-    // try the pixel to the 'left' (counter-clockwise). If that doesn't exist,
+    // try the pixel to the 'right' (turn_right). If that doesn't exist,
+    // try the pixel to the 'left' (turn_left). If that doesn't exist,
     // go back the way we came
-    fn get_next_pixels(&self) -> Vec<(Direction, Pixel)> {
-        let ins = &self.instructions;
+    fn get_next_pixels(&self) -> Vec<(Direction, MatrixPoint, Pixel)> {
         let dir = self.direction;
         let mut next_pixels = vec![];
-        if let Some(point) = ins.go(self.pc, dir) {
+        if let Some((point, pixel)) = self.step(dir) {
             // forward
-            next_pixels.push((dir, point));
+            next_pixels.push((dir, point, pixel));
         }
-        if let Some(point) = ins.go(self.pc, dir.counter_clockwise().opposite()) {
+        if let Some((point, pixel)) = self.step(dir.turn_right()) {
             // right
-            next_pixels.push((dir.counter_clockwise().opposite(), point));
+            next_pixels.push((dir.turn_right(), point, pixel));
         }
-        if let Some(point) = ins.go(self.pc, dir.counter_clockwise()) {
+        if let Some((point, pixel)) = self.step(dir.turn_left()) {
             // left
-            next_pixels.push((dir.counter_clockwise(), point));
+            next_pixels.push((dir.turn_left(), point, pixel));
         }
-        if let Some(point) = ins.go(self.pc, dir.opposite()) {
+        if let Some((point, pixel)) = self.step(dir.opposite()) {
             // back
-            next_pixels.push((dir.opposite(), point));
+            next_pixels.push((dir.opposite(), point, pixel));
         }
         next_pixels
     }
 
-    fn get_next_pixel(&self) -> (Direction, Pixel) {
-        self.get_next_pixels()[0]
+    // resolve the pixel (and its position) that lies one step away from `self.pc` in
+    // `dir`, if any. `Matrix::go` already returns `None` at the edge of the matrix; we just
+    // also need the coordinates it landed on, which it doesn't give us back.
+    fn step(&self, dir: Direction) -> Option<(MatrixPoint, Pixel)> {
+        let pixel = self.instructions.go(self.pc, dir)?;
+        let MatrixPoint(x, y) = self.pc;
+        let (dx, dy) = dir.offset();
+        let point = MatrixPoint(x.checked_add_signed(dx)?, y.checked_add_signed(dy)?);
+        Some((point, pixel))
     }
 
     fn find_start(&self) -> MatrixPoint {
@@ -132,7 +461,7 @@ impl VM {
 
 #[cfg(test)]
 mod test {
-    use super::VM;
+    use super::{Direction, Halt, VM};
     use crate::{Matrix, MatrixPoint, Pixel};
 
     fn init_vm(matrix: Vec<Vec<u16>>) -> VM {
@@ -152,7 +481,7 @@ mod test {
 
     #[test]
     fn test_start_one_d() {
-        let mut vm = init_vm(vec![vec![
+        let vm = init_vm(vec![vec![
             300, 180, 180, 36, 1, 36, 2, 108, 36, 48, 108, 306,
         ]]);
 
@@ -163,7 +492,7 @@ mod test {
 
     #[test]
     fn test_start_two_d() {
-        let mut vm = init_vm(vec![
+        let vm = init_vm(vec![
             vec![0, 180, 180, 36, 1, 36, 2, 108, 36, 48, 108, 306],
             vec![0, 180, 180, 36, 1, 300, 2, 108, 36, 48, 108, 306],
             vec![0, 180, 180, 36, 1, 36, 2, 108, 36, 48, 108, 306],
@@ -176,7 +505,7 @@ mod test {
 
     #[test]
     fn test_start_bounds() {
-        let mut vm = init_vm(vec![
+        let vm = init_vm(vec![
             vec![0, 180, 180, 36, 1, 36, 2, 108, 36, 48, 108, 306],
             vec![0, 180, 180, 36, 1, 3, 2, 108, 36, 48, 108, 306],
             vec![0, 180, 180, 36, 1, 36, 2, 108, 36, 48, 108, 300],
@@ -186,4 +515,146 @@ mod test {
 
         assert_eq!(start, MatrixPoint(11, 2));
     }
+
+    #[test]
+    fn test_execute_halts_on_reaching_halt_instruction() {
+        let mut vm = VM::new();
+
+        let halt = vm.execute(init_matrix(vec![vec![300, 0, 324]]));
+
+        assert_eq!(halt, Halt::Normal);
+    }
+
+    // regression test: a junction where Halt sits directly ahead and a Road also
+    // branches off to one side used to route around the Halt and onto the Road,
+    // since the road-priority search in `get_next_step` didn't know about Halt.
+    #[test]
+    fn test_get_next_step_prefers_halt_ahead_over_a_road_side_branch() {
+        let mut vm = init_vm(vec![vec![0, 0, 0], vec![300, 0, 324]]);
+        vm.pc = MatrixPoint(1, 1);
+        vm.direction = Direction::East;
+
+        let next = vm.get_next_step();
+
+        assert_eq!(next, Some((Direction::East, MatrixPoint(2, 1))));
+    }
+
+    #[test]
+    fn test_execute_prefers_halt_ahead_over_a_road_side_branch() {
+        let mut vm = VM::new();
+
+        let halt = vm.execute(init_matrix(vec![vec![0, 0, 0], vec![300, 0, 324]]));
+
+        assert_eq!(halt, Halt::Normal);
+    }
+
+    #[test]
+    fn test_execute_mirror_redirects_the_walker_to_halt() {
+        let mut vm = VM::new();
+
+        let halt = vm.execute(init_matrix(vec![vec![0, 324], vec![300, 204]]));
+
+        assert_eq!(halt, Halt::Normal);
+    }
+
+    #[test]
+    fn test_execute_splitter_forks_into_two_walkers_that_each_reach_halt() {
+        let mut vm = VM::new();
+
+        let halt = vm.execute(init_matrix(vec![
+            vec![0, 324, 0],
+            vec![300, 240, 0],
+            vec![0, 324, 0],
+        ]));
+
+        assert_eq!(halt, Halt::Normal);
+    }
+
+    // regression test: a walker bouncing between the same two cells forever (here, a
+    // dead end where `Push` keeps growing the stack each pass, so the full machine
+    // state never repeats) must still be dropped once it revisits one of its own
+    // `(position, direction)` pairs, rather than running the step budget dry.
+    #[test]
+    fn test_execute_with_budget_drops_a_walker_that_revisits_its_own_state() {
+        let mut vm = VM::new();
+
+        let halt = vm.execute_with_budget(init_matrix(vec![vec![300, 36]]), 1_000);
+
+        assert_eq!(halt, Halt::Normal);
+    }
+
+    #[test]
+    fn test_execute_with_budget_reports_a_genuine_cycle() {
+        let mut vm = VM::new();
+
+        let halt = vm.execute_with_budget(init_matrix(vec![vec![300, 0]]), 1_000);
+
+        assert_eq!(
+            halt,
+            Halt::Cycle {
+                start_step: 1,
+                period: 2
+            }
+        );
+    }
+
+    // regression test: a splitter forking into a walker that reaches `Halt` and a
+    // sibling that bounces forever in a dead-end pocket (its own full machine state
+    // repeats) must resolve to `Halt::Normal`, not `Halt::Cycle` — the looping sibling
+    // gets dropped once its state repeats, but that drop must not discard the fact that
+    // another walker already finished legitimately.
+    #[test]
+    fn test_execute_with_budget_one_fork_halting_outweighs_a_sibling_stuck_in_a_cycle() {
+        let mut vm = VM::new();
+
+        let halt = vm.execute_with_budget(
+            init_matrix(vec![
+                vec![0, 324, 0, 0],
+                vec![300, 240, 0, 0],
+                vec![0, 0, 0, 0],
+                vec![48, 0, 0, 48],
+                vec![0, 0, 0, 0],
+            ]),
+            1_000,
+        );
+
+        assert_eq!(halt, Halt::Normal);
+    }
+
+    #[test]
+    fn test_direction_clockwise_is_a_four_cycle() {
+        let mut dir = Direction::North;
+        for expected in [
+            Direction::East,
+            Direction::South,
+            Direction::West,
+            Direction::North,
+        ] {
+            dir = dir.clockwise();
+            assert_eq!(dir, expected);
+        }
+    }
+
+    #[test]
+    fn test_direction_counter_clockwise_undoes_clockwise() {
+        for dir in Direction::all() {
+            assert_eq!(dir.clockwise().counter_clockwise(), dir);
+        }
+    }
+
+    #[test]
+    fn test_direction_opposite_is_its_own_inverse() {
+        for dir in Direction::all() {
+            assert_eq!(dir.opposite().opposite(), dir);
+        }
+    }
+
+    #[test]
+    fn test_direction_offset_matches_opposite_direction() {
+        for dir in Direction::all() {
+            let (dx, dy) = dir.offset();
+            let (ox, oy) = dir.opposite().offset();
+            assert_eq!((dx, dy), (-ox, -oy));
+        }
+    }
 }